@@ -1,30 +1,190 @@
 use std::{
-  fs::File,
-  io::{BufRead, BufReader},
-  path::PathBuf,
-  sync::{
-    atomic::{AtomicU64, Ordering},
-    Arc,
-  },
+  path::{Path, PathBuf},
+  sync::atomic::{AtomicU64, Ordering},
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use byteorder::{BigEndian, ByteOrder};
 use chrono::NaiveDateTime;
 use indexmap::IndexMap;
 use indicatif::{ProgressBar, ProgressStyle};
 use serde::Deserialize;
-use sqlx::MySqlPool;
+use sqlx::{MySqlPool, QueryBuilder};
 use structopt::StructOpt;
-use tokio::sync::{RwLock, Semaphore};
 
-/// Import Caddy logs to MySQL for analysis.
+use input::LineSource;
+
+mod input;
+mod serve;
+
 #[derive(StructOpt)]
-struct Opt {
-  /// Path to Caddy's log file in JSON format.
-  input: PathBuf,
+enum Opt {
+  /// Import a Caddy log file into MySQL.
+  Import(ImportOpt),
+
+  /// Serve read-only analytics over the imported logs via HTTP.
+  Serve(serve::ServeOpt),
+}
+
+/// Only lines whose `msg` field equals this are imported, unless overridden.
+const DEFAULT_MSG_FILTER: &str = "handled request";
+
+/// Default batch size, unless overridden.
+const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// Import Caddy logs to MySQL for analysis. Either pass `input`/`output` directly, or supply
+/// one or more `[[import]]` profiles via `--config`; any positional/flag arguments given here
+/// are applied on top of every profile loaded from the config file.
+#[derive(StructOpt)]
+struct ImportOpt {
+  /// Path to Caddy's log file in JSON format. Also accepts a glob pattern or a directory,
+  /// in which case every matching file is imported oldest-first; `.gz` and `.zst` members
+  /// are transparently decompressed. Required unless supplied by `--config`.
+  input: Option<PathBuf>,
+
+  /// MySQL connection string. Required unless supplied by `--config`.
+  output: Option<String>,
+
+  /// TOML file supplying one or more `[[import]]` profiles, each its own input/output/settings,
+  /// so a single run can fan several log sources into one or more databases.
+  #[structopt(long)]
+  config: Option<PathBuf>,
+
+  /// Number of rows to accumulate before issuing a batched insert.
+  #[structopt(long)]
+  batch_size: Option<usize>,
+
+  /// Keep the input file open after reaching EOF and ingest new lines as Caddy
+  /// appends them, reopening the path on rotation/truncation.
+  #[structopt(long)]
+  follow: bool,
+
+  /// Path to the sled database used to checkpoint resume progress. Defaults to
+  /// the input path with a `.ckpt` suffix.
+  #[structopt(long)]
+  checkpoint_db: Option<PathBuf>,
+
+  /// Only import lines whose `msg` field equals this string.
+  #[structopt(long)]
+  msg_filter: Option<String>,
+}
+
+/// One fully-resolved `[[import]]` profile: an input set, a target database, and its own
+/// batching/follow/checkpoint/filter settings.
+#[derive(Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+struct ImportProfile {
+  input: Option<PathBuf>,
+  output: Option<String>,
+  batch_size: Option<usize>,
+  #[serde(default)]
+  follow: bool,
+  checkpoint_db: Option<PathBuf>,
+  msg_filter: Option<String>,
+}
+
+/// Top-level shape of a `--config` TOML file.
+#[derive(Deserialize, Default)]
+struct ConfigFile {
+  #[serde(default)]
+  import: Vec<ImportProfile>,
+}
+
+/// Loads `[[import]]` profiles from `--config` (if any), falling back to a single profile built
+/// from CLI args alone, then applies every CLI arg given on top of each profile so a single flag
+/// (e.g. `--batch-size`) can override it for the whole fan-out. Every profile is checked for a
+/// resolvable input and a connectable output before any of them start ingesting.
+async fn load_profiles(opt: &ImportOpt) -> Result<Vec<(ImportProfile, MySqlPool)>> {
+  let mut profiles = if let Some(config_path) = &opt.config {
+    let cfg: ConfigFile = config::Config::builder()
+      .add_source(config::File::from(config_path.as_path()))
+      .build()?
+      .try_deserialize()?;
+    if cfg.import.is_empty() {
+      vec![ImportProfile::default()]
+    } else {
+      cfg.import
+    }
+  } else {
+    vec![ImportProfile::default()]
+  };
+
+  for profile in &mut profiles {
+    if let Some(input) = &opt.input {
+      profile.input = Some(input.clone());
+    }
+    if let Some(output) = &opt.output {
+      profile.output = Some(output.clone());
+    }
+    if let Some(batch_size) = opt.batch_size {
+      profile.batch_size = Some(batch_size);
+    }
+    if opt.follow {
+      profile.follow = true;
+    }
+    if let Some(checkpoint_db) = &opt.checkpoint_db {
+      profile.checkpoint_db = Some(checkpoint_db.clone());
+    }
+    if let Some(msg_filter) = &opt.msg_filter {
+      profile.msg_filter = Some(msg_filter.clone());
+    }
+  }
+
+  let mut resolved = Vec::with_capacity(profiles.len());
+  for profile in profiles {
+    let input = profile
+      .input
+      .clone()
+      .context("import profile is missing `input`; pass it positionally or set it in --config")?;
+    let output = profile
+      .output
+      .clone()
+      .context("import profile is missing `output`; pass it positionally or set it in --config")?;
+
+    if input::resolve_members(&input)?.is_empty() {
+      anyhow::bail!("input {} did not match any files", input.to_string_lossy());
+    }
+    let db = MySqlPool::connect(&output)
+      .await
+      .with_context(|| format!("connecting to {output}"))?;
+
+    resolved.push((profile, db));
+  }
+
+  Ok(resolved)
+}
+
+/// Tracks, per `file_id`, the highest `line_no` that MySQL has acknowledged inserting,
+/// so a re-run of a partially-imported file can skip past already-committed lines
+/// without re-decoding them.
+struct Checkpoints {
+  db: sled::Db,
+}
+
+impl Checkpoints {
+  fn open(path: &Path) -> Result<Self> {
+    Ok(Self {
+      db: sled::open(path)?,
+    })
+  }
+
+  /// The last `line_no` committed for `file_id`, if any.
+  fn get(&self, file_id: &str) -> Result<Option<u64>> {
+    match self.db.get(file_id)? {
+      Some(bytes) => Ok(Some(BigEndian::read_u64(&bytes))),
+      None => Ok(None),
+    }
+  }
 
-  /// MySQL connection string.
-  output: String,
+  /// Records `line_no` as the last committed line for `file_id`. Only call this once
+  /// MySQL has acknowledged the corresponding rows.
+  fn advance(&self, file_id: &str, line_no: u64) -> Result<()> {
+    let mut bytes = [0u8; 8];
+    BigEndian::write_u64(&mut bytes, line_no);
+    self.db.insert(file_id, &bytes)?;
+    self.db.flush()?;
+    Ok(())
+  }
 }
 
 #[derive(Deserialize)]
@@ -54,6 +214,30 @@ struct Stats {
   rows_processed: AtomicU64,
 }
 
+/// The bound values for one row of the `logs` table, staged until the batch is flushed.
+struct PendingRow {
+  file_id: String,
+  line_no: u64,
+  ts: NaiveDateTime,
+  user_id: String,
+  duration: f64,
+  size: u64,
+  status_code: u16,
+  resp_headers: String,
+  remote_addr: String,
+  proto: String,
+  method: String,
+  host: String,
+  uri: String,
+  req_headers: String,
+}
+
+/// Number of `?` placeholders bound per row of the `logs` table.
+const COLUMNS_PER_ROW: usize = 14;
+
+/// MySQL caps a single statement at 65535 placeholders.
+const MAX_PLACEHOLDERS: usize = 65535;
+
 fn main() -> Result<()> {
   tokio::runtime::Builder::new_current_thread()
     .enable_all()
@@ -65,30 +249,97 @@ fn main() -> Result<()> {
 async fn async_main() -> Result<()> {
   tracing_subscriber::fmt::init();
 
-  let opt = Opt::from_args();
-  let logfile = BufReader::new(File::open(&opt.input)?);
-  let db = MySqlPool::connect(&opt.output).await?;
-
-  sqlx::migrate!().run(&db).await?;
+  match Opt::from_args() {
+    Opt::Import(opt) => import(opt).await,
+    Opt::Serve(opt) => serve::serve(opt).await,
+  }
+}
 
-  eprintln!("Importing from file {}.", opt.input.to_string_lossy());
-
-  // The file id is the BLAKE3 hash of the first line
-  let mut file_id: Option<String> = None;
-  let insertion_concurrency = Arc::new(Semaphore::new(50));
-  let insertion_busy = Arc::new(RwLock::new(()));
-  let stats: Arc<Stats> = Arc::new(Default::default());
+async fn import(opt: ImportOpt) -> Result<()> {
+  let profiles = load_profiles(&opt).await?;
 
   let spinner_style = ProgressStyle::default_spinner().template("{spinner} {wide_msg}");
   let pb = ProgressBar::new(0);
   pb.set_style(spinner_style);
+  let stats: Stats = Default::default();
+
+  for (profile, db) in &profiles {
+    sqlx::migrate!().run(db).await?;
+
+    let input = profile.input.clone().unwrap();
+    let checkpoint_db_path = profile.checkpoint_db.clone().unwrap_or_else(|| {
+      let mut path = input.clone().into_os_string();
+      path.push(".ckpt");
+      PathBuf::from(path)
+    });
+    let checkpoints = Checkpoints::open(&checkpoint_db_path)?;
+
+    let max_rows = MAX_PLACEHOLDERS / COLUMNS_PER_ROW;
+    let batch_size = profile.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).clamp(1, max_rows);
+    let msg_filter = profile.msg_filter.clone().unwrap_or_else(|| DEFAULT_MSG_FILTER.to_string());
+
+    let members = input::resolve_members(&input)?;
+    eprintln!(
+      "Importing from {} file(s) matching {}.",
+      members.len(),
+      input.to_string_lossy()
+    );
 
-  for (i, line) in logfile.lines().enumerate() {
-    let line_no = i + 1;
-    let line = line?;
+    for (i, member) in members.iter().enumerate() {
+      // Only the last member of the set can be a live file worth tailing.
+      let follow = profile.follow && i == members.len() - 1;
+      import_member(member, follow, &msg_filter, db, &checkpoints, batch_size, &stats, &pb).await?;
+    }
+  }
+
+  pb.finish();
+  eprintln!("Success.");
+
+  Ok(())
+}
+
+/// Imports a single physical file (a member of the resolved input set), deriving its own
+/// `file_id` and resume checkpoint independently of any sibling members.
+#[allow(clippy::too_many_arguments)]
+async fn import_member(
+  path: &Path,
+  follow: bool,
+  msg_filter: &str,
+  db: &MySqlPool,
+  checkpoints: &Checkpoints,
+  batch_size: usize,
+  stats: &Stats,
+  pb: &ProgressBar,
+) -> Result<()> {
+  tracing::info!(path = %path.to_string_lossy(), "importing member");
+  let mut logfile = LineSource::open(path.to_path_buf(), follow)?;
+
+  // The file id is the BLAKE3 hash of the first line matching `msg_filter`.
+  let mut file_id: Option<String> = None;
+  let mut checkpoint: Option<u64> = None;
+  let mut batch: Vec<PendingRow> = Vec::with_capacity(batch_size);
+
+  let mut line_no = 0usize;
+  while let Some(event) = logfile.next_line().await? {
+    let line = match event {
+      input::Line::Data(line) => line,
+      input::Line::Idle => {
+        // The tail is caught up to EOF; flush whatever's buffered instead of holding it
+        // until batch_size lines accumulate, which could otherwise take forever on a
+        // live, low-traffic log.
+        flush_batch(db, &mut batch, stats, pb, checkpoints).await?;
+        continue;
+      }
+    };
+    line_no += 1;
     if line.is_empty() {
       continue;
     }
+    if let Some(cp) = checkpoint {
+      if line_no as u64 <= cp {
+        continue;
+      }
+    }
     let pre_decoded: serde_json::Value = match serde_json::from_str(&line) {
       Ok(x) => x,
       Err(e) => {
@@ -99,14 +350,19 @@ async fn async_main() -> Result<()> {
     if !pre_decoded
       .get("msg")
       .and_then(|x| x.as_str())
-      .map(|x| x == "handled request")
+      .map(|x| x == msg_filter)
       .unwrap_or(false)
     {
       continue;
     }
 
     if file_id.is_none() {
-      file_id = Some(blake3::hash(line.as_bytes()).to_hex().to_string());
+      let fid = blake3::hash(line.as_bytes()).to_hex().to_string();
+      checkpoint = checkpoints.get(&fid)?;
+      if let Some(cp) = checkpoint {
+        tracing::info!(file_id = %fid, checkpoint = cp, "resuming from checkpoint");
+      }
+      file_id = Some(fid);
       tracing::info!(file_id = %file_id.as_ref().unwrap(), "generated file id");
     }
     let file_id = file_id.as_ref().unwrap().clone();
@@ -119,80 +375,113 @@ async fn async_main() -> Result<()> {
       }
     };
 
-    let permit = insertion_concurrency.clone().acquire_owned().await.unwrap();
-    let busy = insertion_busy.clone().read_owned().await;
-    let db = db.clone();
-    let stats = stats.clone();
-    let pb = pb.clone();
-    tokio::spawn(async move {
-      let res = sqlx::query!(
-        r#"
-        insert ignore into logs
-        (
-          file_id,
-          line_no,
-          ts,
-          user_id,
-          duration,
-          size,
-          status_code,
-          resp_headers,
-          remote_addr,
-          proto,
-          method,
-          host,
-          uri,
-          req_headers
-        ) values(
-          ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
-        )
-      "#,
-        file_id,
-        line_no as u64,
-        NaiveDateTime::from_timestamp(entry.ts as i64, (entry.ts.fract() * 1_000_000_000.0) as u32),
-        entry.user_id.as_ref().map(|x| x.as_str()).unwrap_or(""),
-        entry.duration,
-        entry.size,
-        entry.status,
-        serde_json::to_string(&entry.resp_headers).unwrap(),
-        entry.request.remote_addr,
-        entry.request.proto,
-        entry.request.method,
-        entry.request.host,
-        entry.request.uri,
-        serde_json::to_string(&entry.request.headers).unwrap(),
-      )
-      .execute(&db)
-      .await;
-
-      match res {
-        Ok(res) => {
-          let rows_inserted = if res.rows_affected() == 0 {
-            tracing::debug!(line_no, "did not insert log entry");
-            stats.rows_inserted.load(Ordering::Relaxed)
-          } else {
-            tracing::debug!(line_no, "inserted log entry");
-            stats.rows_inserted.fetch_add(1, Ordering::Relaxed) + 1
-          };
-          let rows_processed = stats.rows_processed.fetch_add(1, Ordering::Relaxed) + 1;
-          pb.set_message(format!(
-            "Adding logs... {}/{}",
-            rows_inserted, rows_processed
-          ));
-          pb.inc(1);
-        }
-        Err(e) => {
-          tracing::error!(line_no, %file_id, error = %e, "failed to insert log entry");
-        }
-      }
-
-      drop(busy);
-      drop(permit);
+    batch.push(PendingRow {
+      file_id,
+      line_no: line_no as u64,
+      ts: NaiveDateTime::from_timestamp(entry.ts as i64, (entry.ts.fract() * 1_000_000_000.0) as u32),
+      user_id: entry.user_id.unwrap_or_default(),
+      duration: entry.duration,
+      size: entry.size,
+      status_code: entry.status,
+      resp_headers: serde_json::to_string(&entry.resp_headers).unwrap(),
+      remote_addr: entry.request.remote_addr,
+      proto: entry.request.proto,
+      method: entry.request.method,
+      host: entry.request.host,
+      uri: entry.request.uri,
+      req_headers: serde_json::to_string(&entry.request.headers).unwrap(),
     });
+
+    if batch.len() >= batch_size {
+      flush_batch(db, &mut batch, stats, pb, checkpoints).await?;
+    }
   }
-  insertion_busy.write().await;
-  pb.finish();
-  eprintln!("Success.");
+  flush_batch(db, &mut batch, stats, pb, checkpoints).await?;
+
+  Ok(())
+}
+
+/// Insert the buffered rows in a single `insert ignore ... values (...),(...),...` statement
+/// run inside one transaction, then clear the buffer. A no-op if the buffer is empty.
+async fn flush_batch(
+  db: &MySqlPool,
+  batch: &mut Vec<PendingRow>,
+  stats: &Stats,
+  pb: &ProgressBar,
+  checkpoints: &Checkpoints,
+) -> Result<()> {
+  if batch.is_empty() {
+    return Ok(());
+  }
+
+  // The batch is strictly ordered by line_no, so the last row is the resume point
+  // once MySQL has acknowledged the whole batch.
+  let file_id = batch.last().unwrap().file_id.clone();
+  let last_line_no = batch.last().unwrap().line_no;
+
+  let mut qb = QueryBuilder::new(
+    "insert ignore into logs (
+      file_id,
+      line_no,
+      ts,
+      user_id,
+      duration,
+      size,
+      status_code,
+      resp_headers,
+      remote_addr,
+      proto,
+      method,
+      host,
+      uri,
+      req_headers
+    ) ",
+  );
+
+  qb.push_values(batch.iter(), |mut b, row| {
+    b.push_bind(&row.file_id)
+      .push_bind(row.line_no)
+      .push_bind(row.ts)
+      .push_bind(&row.user_id)
+      .push_bind(row.duration)
+      .push_bind(row.size)
+      .push_bind(row.status_code)
+      .push_bind(&row.resp_headers)
+      .push_bind(&row.remote_addr)
+      .push_bind(&row.proto)
+      .push_bind(&row.method)
+      .push_bind(&row.host)
+      .push_bind(&row.uri)
+      .push_bind(&row.req_headers);
+  });
+
+  let processed = batch.len() as u64;
+  let mut tx = db.begin().await?;
+  let res = qb.build().execute(&mut *tx).await;
+  let res = match res {
+    Ok(res) => {
+      tx.commit().await?;
+      res
+    }
+    Err(e) => {
+      // Drop just this batch and keep importing, matching the per-row resilience of the
+      // original implementation: one malformed/oversized batch shouldn't abort a run that's
+      // otherwise multiple millions of lines in.
+      tracing::error!(error = %e, rows = processed, "failed to insert batch, dropping it and continuing");
+      batch.clear();
+      return Ok(());
+    }
+  };
+
+  let inserted = stats.rows_inserted.fetch_add(res.rows_affected(), Ordering::Relaxed) + res.rows_affected();
+  let total_processed = stats.rows_processed.fetch_add(processed, Ordering::Relaxed) + processed;
+  pb.set_message(format!("Adding logs... {}/{}", inserted, total_processed));
+  pb.inc(processed);
+
+  // Only advance the checkpoint now that MySQL has acknowledged these rows, so a
+  // crash mid-run at worst reprocesses the still-unacknowledged tail.
+  checkpoints.advance(&file_id, last_line_no)?;
 
+  batch.clear();
   Ok(())
 }
@@ -0,0 +1,198 @@
+use std::{convert::Infallible, net::SocketAddr, time::Duration};
+
+use anyhow::Result;
+use axum::{
+  extract::{Query, State},
+  response::sse::{Event, KeepAlive, Sse},
+  routing::get,
+  Router,
+};
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{MySqlPool, Row};
+use structopt::StructOpt;
+
+/// Serve read-only analytics over the `logs` table via HTTP.
+#[derive(StructOpt)]
+pub struct ServeOpt {
+  /// MySQL connection string.
+  output: String,
+
+  /// Address to bind the HTTP server to.
+  #[structopt(long, default_value = "127.0.0.1:8080")]
+  bind: SocketAddr,
+}
+
+/// Query params shared by the `/stats/*` endpoints: an optional time window and host filter.
+#[derive(Deserialize)]
+struct StatsQuery {
+  from: Option<chrono::NaiveDateTime>,
+  to: Option<chrono::NaiveDateTime>,
+  host: Option<String>,
+}
+
+#[derive(Serialize)]
+struct StatusRow {
+  status_code: u16,
+  count: i64,
+}
+
+#[derive(Serialize)]
+struct TopUriRow {
+  uri: String,
+  count: i64,
+}
+
+#[derive(Serialize)]
+struct LatencyRow {
+  percentile: &'static str,
+  duration: f64,
+}
+
+pub async fn serve(opt: ServeOpt) -> Result<()> {
+  let db = MySqlPool::connect(&opt.output).await?;
+  sqlx::migrate!().run(&db).await?;
+
+  let app = Router::new()
+    .route("/stats/status", get(stats_status))
+    .route("/stats/top-uris", get(stats_top_uris))
+    .route("/stats/latency", get(stats_latency))
+    .with_state(db);
+
+  tracing::info!(addr = %opt.bind, "starting analytics server");
+  axum::Server::bind(&opt.bind)
+    .serve(app.into_make_service())
+    .await?;
+
+  Ok(())
+}
+
+/// Appends the `from`/`to`/`host` filters in `q` to the query in `qb` as a `where` clause,
+/// binding them in the same order they're pushed. Assumes the query built so far ends right
+/// before its `group by`/`order by`.
+fn push_filters<'a>(qb: &mut sqlx::QueryBuilder<'a, sqlx::MySql>, q: &'a StatsQuery) {
+  let mut first = true;
+  let mut push_clause = |qb: &mut sqlx::QueryBuilder<'a, sqlx::MySql>, clause: &str| {
+    qb.push(if first { " where " } else { " and " });
+    first = false;
+    qb.push(clause);
+  };
+  if let Some(from) = &q.from {
+    push_clause(qb, "ts >= ");
+    qb.push_bind(*from);
+  }
+  if let Some(to) = &q.to {
+    push_clause(qb, "ts <= ");
+    qb.push_bind(*to);
+  }
+  if let Some(host) = &q.host {
+    push_clause(qb, "host = ");
+    qb.push_bind(host);
+  }
+}
+
+/// Turns a fallible DB row into an SSE event, logging and surfacing an `error` event instead
+/// of silently dropping the row if the query or the row's shape didn't match expectations.
+fn row_event<T: Serialize>(
+  row: sqlx::Result<sqlx::mysql::MySqlRow>,
+  decode: impl FnOnce(&sqlx::mysql::MySqlRow) -> sqlx::Result<T>,
+) -> Result<Event, Infallible> {
+  let result = row.and_then(|row| decode(&row));
+  match result {
+    Ok(out) => Ok(Event::default().json_data(out).unwrap()),
+    Err(err) => {
+      tracing::error!(error = %err, "stats query row failed");
+      Ok(Event::default().event("error").data(err.to_string()))
+    }
+  }
+}
+
+/// Status-code histogram, streamed as one SSE event per distinct status code.
+async fn stats_status(
+  State(db): State<MySqlPool>,
+  Query(q): Query<StatsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+  let mut qb = sqlx::QueryBuilder::new("select status_code, count(*) as count from logs");
+  push_filters(&mut qb, &q);
+  qb.push(" group by status_code order by status_code");
+
+  let rows = qb.build().fetch(&db).map(|row| {
+    row_event(row, |row| {
+      Ok(StatusRow {
+        status_code: row.try_get("status_code")?,
+        count: row.try_get("count")?,
+      })
+    })
+  });
+
+  Sse::new(rows).keep_alive(KeepAlive::default())
+}
+
+/// Most-requested URIs, streamed as one SSE event per URI.
+async fn stats_top_uris(
+  State(db): State<MySqlPool>,
+  Query(q): Query<StatsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+  let mut qb = sqlx::QueryBuilder::new("select uri, count(*) as count from logs");
+  push_filters(&mut qb, &q);
+  qb.push(" group by uri order by count desc limit 100");
+
+  let rows = qb.build().fetch(&db).map(|row| {
+    row_event(row, |row| {
+      Ok(TopUriRow {
+        uri: row.try_get("uri")?,
+        count: row.try_get("count")?,
+      })
+    })
+  });
+
+  Sse::new(rows).keep_alive(KeepAlive::default())
+}
+
+/// Request-duration percentiles, streamed as one SSE event per percentile bucket. The matching
+/// `duration` column is sorted once in a single query and all percentiles are read off of that
+/// one sorted result, rather than issuing a separate `order by ... limit 1 offset <n>` scan per
+/// percentile (which would re-sort the same rows once per bucket).
+async fn stats_latency(
+  State(db): State<MySqlPool>,
+  Query(q): Query<StatsQuery>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+  const PERCENTILES: &[(&str, f64)] = &[("p50", 0.50), ("p90", 0.90), ("p99", 0.99)];
+
+  let mut qb = sqlx::QueryBuilder::new("select duration from logs");
+  push_filters(&mut qb, &q);
+  qb.push(" order by duration");
+
+  let durations: Vec<f64> = match qb.build().fetch_all(&db).await {
+    Ok(rows) => rows
+      .into_iter()
+      .filter_map(|row| row.try_get::<f64, _>("duration").ok())
+      .collect(),
+    Err(err) => {
+      tracing::error!(error = %err, "stats_latency: fetching durations failed");
+      Vec::new()
+    }
+  };
+
+  let rows: Vec<LatencyRow> = PERCENTILES
+    .iter()
+    .map(|(name, p)| {
+      let duration = if durations.is_empty() {
+        0.0
+      } else {
+        let idx = ((durations.len() - 1) as f64 * p).round() as usize;
+        durations[idx]
+      };
+      LatencyRow {
+        percentile: name,
+        duration,
+      }
+    })
+    .collect();
+
+  let events = futures::stream::iter(rows)
+    .then(|row| async move { Ok(Event::default().json_data(row).unwrap()) })
+    .chain(futures::stream::once(async { Ok(Event::default().comment("end")) }));
+
+  Sse::new(events).keep_alive(KeepAlive::default().interval(Duration::from_secs(15)))
+}